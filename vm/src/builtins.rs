@@ -0,0 +1,37 @@
+/*
+ * The `isinstance`/`issubclass` builtins.
+ *
+ * These used to call straight into `objtype::isinstance`/`objtype::issubclass`
+ * (a plain MRO walk), which meant a metaclass's `__instancecheck__`/
+ * `__subclasscheck__` (how `abc.ABCMeta` and `typing.Protocol` hook in) was
+ * never consulted for user-facing calls. They now go through
+ * `objtype::real_isinstance`/`objtype::real_issubclass`, which check the
+ * metaclass first and fall back to the MRO walk.
+ */
+
+use super::obj::objtype;
+use super::pyobject::{PyFuncArgs, PyResult};
+use super::vm::VirtualMachine;
+
+pub fn builtin_isinstance(vm: &mut VirtualMachine, args: PyFuncArgs) -> PyResult {
+    arg_check!(
+        vm,
+        args,
+        required = [(obj, None), (typ, Some(vm.ctx.type_type()))]
+    );
+    let result = objtype::real_isinstance(vm, obj, typ)?;
+    Ok(vm.context().new_bool(result))
+}
+
+pub fn builtin_issubclass(vm: &mut VirtualMachine, args: PyFuncArgs) -> PyResult {
+    arg_check!(
+        vm,
+        args,
+        required = [
+            (subclass, Some(vm.ctx.type_type())),
+            (typ, Some(vm.ctx.type_type()))
+        ]
+    );
+    let result = objtype::real_issubclass(vm, subclass, typ)?;
+    Ok(vm.context().new_bool(result))
+}
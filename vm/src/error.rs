@@ -0,0 +1,185 @@
+/*
+ * Structured diagnostic information carried by exceptions.
+ *
+ * This mirrors the main-message/sub-message/hint/location shape used by
+ * compiler-style diagnostics: a short headline, an ordered list of
+ * sub-messages (each optionally anchored to a source span), and an optional
+ * "did you mean" style hint. Exception sites that have this richer context
+ * available (e.g. attribute lookup) populate it; everything else keeps using
+ * a plain message built without an `ErrorCore`, so those exceptions carry no
+ * `sub_messages`/`hint`/`location` attributes.
+ *
+ * `ErrorCore::into_exception` builds the exception's message from
+ * `render()`, so printing the exception (its default `str`/traceback) shows
+ * the full multi-line report - sub-messages, locations and the "did you
+ * mean" hint included - rather than just the headline. The individual
+ * fields are *also* attached as `sub_messages`/`hint`/`location` attributes,
+ * so tooling that wants the structured data back can use `get_attr` instead
+ * of re-parsing the rendered string.
+ */
+
+use super::pyobject::{AttributeProtocol, PyObjectRef};
+use super::vm::VirtualMachine;
+
+#[derive(Debug, Clone)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct SubMessage {
+    pub message: String,
+    pub location: Option<Location>,
+}
+
+impl SubMessage {
+    pub fn new(message: String, location: Option<Location>) -> Self {
+        SubMessage { message, location }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ErrorCore {
+    pub message: String,
+    pub sub_messages: Vec<SubMessage>,
+    pub hint: Option<String>,
+    pub location: Option<Location>,
+}
+
+impl ErrorCore {
+    pub fn new(message: String) -> Self {
+        ErrorCore {
+            message,
+            sub_messages: vec![],
+            hint: None,
+            location: None,
+        }
+    }
+
+    pub fn with_sub_message(mut self, message: String, location: Option<Location>) -> Self {
+        self.sub_messages.push(SubMessage::new(message, location));
+        self
+    }
+
+    pub fn with_hint(mut self, hint: String) -> Self {
+        self.hint = Some(hint);
+        self
+    }
+
+    pub fn with_location(mut self, location: Location) -> Self {
+        self.location = Some(location);
+        self
+    }
+
+    /// Render this error as a multi-line, traceback-style string, while
+    /// keeping the individual fields available for tooling to consume
+    /// directly.
+    pub fn render(&self) -> String {
+        let mut out = self.message.clone();
+        if let Some(location) = &self.location {
+            out.push_str(&format!(
+                " (line {}, column {})",
+                location.line, location.column
+            ));
+        }
+        for sub in &self.sub_messages {
+            out.push('\n');
+            out.push_str("  ");
+            if let Some(location) = &sub.location {
+                out.push_str(&format!(
+                    "(line {}, column {}) ",
+                    location.line, location.column
+                ));
+            }
+            out.push_str(&sub.message);
+        }
+        if let Some(hint) = &self.hint {
+            out.push('\n');
+            out.push_str("  hint: ");
+            out.push_str(hint);
+        }
+        out
+    }
+
+    /// Build a Python exception of `exc_class` carrying this core: the
+    /// exception's message is the full `render()`ed report, so the default
+    /// `str`/traceback of the exception shows the sub-messages and hint
+    /// rather than just the headline; `sub_messages`/`hint`/`location` are
+    /// also attached as real attributes so tooling can read them back with
+    /// `get_attr` instead of re-parsing the rendered string.
+    pub fn into_exception(self, vm: &mut VirtualMachine, exc_class: PyObjectRef) -> PyObjectRef {
+        let exc = vm.new_exception(exc_class, self.render());
+
+        let sub_messages: Vec<PyObjectRef> = self
+            .sub_messages
+            .iter()
+            .map(|sub| {
+                let location = location_to_pyobject(vm, &sub.location);
+                vm.context()
+                    .new_tuple(vec![vm.new_str(sub.message.clone()), location])
+            })
+            .collect();
+        exc.set_attr("sub_messages", vm.context().new_tuple(sub_messages));
+
+        let hint = match &self.hint {
+            Some(hint) => vm.new_str(hint.clone()),
+            None => vm.get_none(),
+        };
+        exc.set_attr("hint", hint);
+
+        let location = location_to_pyobject(vm, &self.location);
+        exc.set_attr("location", location);
+
+        exc
+    }
+}
+
+fn location_to_pyobject(vm: &mut VirtualMachine, location: &Option<Location>) -> PyObjectRef {
+    match location {
+        Some(location) => vm.context().new_tuple(vec![
+            vm.context().new_int(location.line as i32),
+            vm.context().new_int(location.column as i32),
+        ]),
+        None => vm.get_none(),
+    }
+}
+
+/// Levenshtein edit distance between two strings, used to compute "did you
+/// mean" hints from a set of candidate names.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for i in 1..=a.len() {
+        let mut prev = row[0];
+        row[0] = i;
+        for j in 1..=b.len() {
+            let cur = row[j];
+            row[j] = if a[i - 1] == b[j - 1] {
+                prev
+            } else {
+                1 + prev.min(row[j]).min(row[j - 1])
+            };
+            prev = cur;
+        }
+    }
+
+    row[b.len()]
+}
+
+/// Find the closest candidate to `name`, if any is close enough to be worth
+/// suggesting.
+pub fn did_you_mean<'a, I>(name: &str, candidates: I) -> Option<String>
+where
+    I: IntoIterator<Item = &'a String>,
+{
+    let max_distance = (name.len() / 3).max(1);
+    candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate.clone())
+}
@@ -1,8 +1,11 @@
+use super::super::error::{did_you_mean, ErrorCore};
 use super::super::pyobject::{
     AttributeProtocol, IdProtocol, PyContext, PyFuncArgs, PyObject, PyObjectKind, PyObjectRef,
     PyResult, TypeProtocol,
 };
+use super::super::typecheck;
 use super::super::vm::VirtualMachine;
+use super::objbool;
 use super::objdict;
 use super::objstr;
 use super::objtype; // Required for arg_check! to use isinstance
@@ -30,6 +33,14 @@ pub fn init(context: &PyContext) {
     type_type.set_attr("__repr__", context.new_rustfunc(type_repr));
     type_type.set_attr("__prepare__", context.new_rustfunc(type_prepare));
     type_type.set_attr("__getattribute__", context.new_rustfunc(type_getattribute));
+    type_type.set_attr(
+        "__instancecheck__",
+        context.new_rustfunc(type_instancecheck),
+    );
+    type_type.set_attr(
+        "__subclasscheck__",
+        context.new_rustfunc(type_subclasscheck),
+    );
 }
 
 fn type_mro(vm: &mut VirtualMachine, args: PyFuncArgs) -> PyResult {
@@ -62,6 +73,10 @@ pub fn base_classes(obj: &PyObjectRef) -> Vec<PyObjectRef> {
     _mro(obj.typ()).unwrap()
 }
 
+// Fast, non-overridable MRO walk. This is what `arg_check!` uses internally,
+// so it must never call back into Python (e.g. via __instancecheck__) or we
+// risk infinite recursion while checking the arguments of the very methods
+// that implement instance/subclass checks.
 pub fn isinstance(obj: &PyObjectRef, cls: &PyObjectRef) -> bool {
     let mro = _mro(obj.typ()).unwrap();
     mro.into_iter().any(|c| c.is(&cls))
@@ -72,6 +87,87 @@ pub fn issubclass(typ: &PyObjectRef, cls: &PyObjectRef) -> bool {
     mro.into_iter().any(|c| c.is(&cls))
 }
 
+// `init()` installs `__instancecheck__`/`__subclasscheck__` on `type` itself
+// so that ordinary classes (whose metaclass is plain `type`) have something
+// to look up below; `mcl.get_attr(...)` would otherwise always succeed and
+// send every call through `vm.invoke`. Comparing the looked-up method
+// against `type`'s own default by identity tells apart a genuine override
+// (`abc.ABCMeta`, `typing.Protocol`, ...) from an ordinary class that just
+// inherited the default, so the common case stays on the Rust MRO walk.
+fn is_default_check(vm: &VirtualMachine, method: &PyObjectRef, attr_name: &str) -> bool {
+    match vm.ctx.type_type().get_attr(attr_name) {
+        Some(default) => default.is(method),
+        None => false,
+    }
+}
+
+// The Python-level `isinstance()`/`issubclass()` builtins dispatch here: give
+// the class's metaclass a chance to override the check (this is how
+// `abc.ABCMeta` and `typing.Protocol` hook in) before falling back to the
+// plain MRO walk above.
+pub fn real_isinstance(
+    vm: &mut VirtualMachine,
+    obj: &PyObjectRef,
+    cls: &PyObjectRef,
+) -> PyResult<bool> {
+    let mcl = cls.typ();
+    if let Some(method) = mcl.get_attr("__instancecheck__") {
+        if !is_default_check(vm, &method, "__instancecheck__") {
+            let ret = vm.invoke(
+                method,
+                PyFuncArgs {
+                    args: vec![cls.clone(), obj.clone()],
+                    kwargs: vec![],
+                },
+            )?;
+            return objbool::boolval(vm, ret);
+        }
+    }
+    Ok(isinstance(obj, cls))
+}
+
+pub fn real_issubclass(
+    vm: &mut VirtualMachine,
+    subclass: &PyObjectRef,
+    cls: &PyObjectRef,
+) -> PyResult<bool> {
+    let mcl = cls.typ();
+    if let Some(method) = mcl.get_attr("__subclasscheck__") {
+        if !is_default_check(vm, &method, "__subclasscheck__") {
+            let ret = vm.invoke(
+                method,
+                PyFuncArgs {
+                    args: vec![cls.clone(), subclass.clone()],
+                    kwargs: vec![],
+                },
+            )?;
+            return objbool::boolval(vm, ret);
+        }
+    }
+    Ok(issubclass(subclass, cls))
+}
+
+fn type_instancecheck(vm: &mut VirtualMachine, args: PyFuncArgs) -> PyResult {
+    arg_check!(
+        vm,
+        args,
+        required = [(cls, Some(vm.ctx.type_type())), (obj, None)]
+    );
+    Ok(vm.context().new_bool(isinstance(obj, cls)))
+}
+
+fn type_subclasscheck(vm: &mut VirtualMachine, args: PyFuncArgs) -> PyResult {
+    arg_check!(
+        vm,
+        args,
+        required = [
+            (cls, Some(vm.ctx.type_type())),
+            (subclass, Some(vm.ctx.type_type()))
+        ]
+    );
+    Ok(vm.context().new_bool(issubclass(subclass, cls)))
+}
+
 pub fn get_type_name(typ: &PyObjectRef) -> String {
     if let PyObjectKind::Class {
         name,
@@ -105,12 +201,36 @@ pub fn type_new(vm: &mut VirtualMachine, args: PyFuncArgs) -> PyResult {
                 (dict, Some(vm.ctx.dict_type()))
             ]
         );
-        let mut bases = vm.extract_elements(bases)?;
-        bases.push(vm.context().object());
+        let mut base_classes = vm.extract_elements(bases)?;
+        base_classes.push(vm.context().object());
+
+        let winner = winning_metaclass(vm, typ, &base_classes, &args.kwargs)?;
+        if !winner.is(typ) {
+            // The metaclass that actually governs this class is not the one
+            // we were invoked with (either an explicit `metaclass=` kwarg or
+            // one inherited from a base). Re-dispatch construction by
+            // calling the winner itself (`winner(name, bases, dict)`), the
+            // same way any other callable is invoked, so it goes through
+            // `winner.typ()`'s `__call__` rather than a `__call__` picked up
+            // from the winner's own MRO.
+            return vm.invoke(
+                winner.clone(),
+                PyFuncArgs {
+                    args: vec![name.clone(), bases.clone(), dict.clone()],
+                    kwargs: args.kwargs.clone(),
+                },
+            );
+        }
+
+        typecheck::check_class_dict(vm, dict)?;
+
         let name = objstr::get_value(name);
-        new(typ.clone(), &name, bases, dict.clone())
+        new(vm, typ.clone(), &name, base_classes, dict.clone())
     } else {
-        Err(vm.new_type_error(format!(": type_new: {:?}", args)))
+        let core = ErrorCore::new("type() takes 1 or 3 arguments".to_string())
+            .with_sub_message(format!("called as type.__new__{:?}", args), None);
+        let type_error = vm.context().exceptions.type_error.clone();
+        Err(core.into_exception(vm, type_error))
     }
 }
 
@@ -187,10 +307,18 @@ pub fn type_getattribute(vm: &mut VirtualMachine, args: PyFuncArgs) -> PyResult
             )
         } else {
             let attribute_error = vm.context().exceptions.attribute_error.clone();
-            Err(vm.new_exception(
-                attribute_error,
-                format!("{:?} object {:?} has no attribute {}", mcl, cls, name),
+            let type_name = get_type_name(&mcl);
+            let attributes = get_attributes(cls);
+            let candidates: Vec<String> = attributes.keys().cloned().collect();
+            let mut core = ErrorCore::new(format!(
+                "'{}' object has no attribute '{}'",
+                type_name, name
             ))
+            .with_sub_message(format!("while looking up '{}' on {:?}", name, cls), None);
+            if let Some(hint) = did_you_mean(&name, candidates.iter()) {
+                core = core.with_hint(format!("did you mean '{}'?", hint));
+            }
+            Err(core.into_exception(vm, attribute_error))
         }
     }
 }
@@ -273,9 +401,90 @@ fn linearise_mro(mut bases: Vec<Vec<PyObjectRef>>) -> Option<Vec<PyObjectRef>> {
     Some(result)
 }
 
-pub fn new(typ: PyObjectRef, name: &str, bases: Vec<PyObjectRef>, dict: PyObjectRef) -> PyResult {
-    let mros = bases.into_iter().map(|x| _mro(x).unwrap()).collect();
-    let mro = linearise_mro(mros).unwrap();
+/// Determine the "winner" metaclass for a class being created: starting from
+/// the explicit `metaclass=` keyword argument if given (otherwise `typ`, the
+/// metaclass that was invoked), the most-derived type among it and
+/// `type(base)` for every base, so a base's metaclass that subclasses the
+/// explicit one replaces it. Errors with a TypeError when two candidates are
+/// unrelated (neither is a subclass of the other).
+fn winning_metaclass(
+    vm: &mut VirtualMachine,
+    typ: &PyObjectRef,
+    bases: &[PyObjectRef],
+    kwargs: &[(String, PyObjectRef)],
+) -> PyResult<PyObjectRef> {
+    let mut winner = kwargs
+        .iter()
+        .find(|(key, _)| key == "metaclass")
+        .map(|(_, value)| value.clone())
+        .unwrap_or_else(|| typ.clone());
+
+    if _mro(winner.clone()).is_none() {
+        // `issubclass` below unwraps `_mro`, so a non-class `metaclass=`
+        // kwarg (e.g. `class C(metaclass=5)`) must be rejected here instead
+        // of panicking.
+        let core = ErrorCore::new("metaclass must be a type".to_string())
+            .with_sub_message(format!("got {:?}", winner), None);
+        let type_error = vm.context().exceptions.type_error.clone();
+        return Err(core.into_exception(vm, type_error));
+    }
+
+    for base in bases {
+        let base_type = base.typ();
+        if issubclass(&winner, &base_type) {
+            // winner is already at least as derived as this base's metaclass
+        } else if issubclass(&base_type, &winner) {
+            winner = base_type;
+        } else {
+            let core = ErrorCore::new(
+                "metaclass conflict: the metaclass of a derived class must be a \
+                 (non-strict) subclass of the metaclasses of all its bases"
+                    .to_string(),
+            )
+            .with_sub_message(
+                format!(
+                    "{} and {} are unrelated",
+                    get_type_name(&winner),
+                    get_type_name(&base_type)
+                ),
+                None,
+            );
+            let type_error = vm.context().exceptions.type_error.clone();
+            return Err(core.into_exception(vm, type_error));
+        }
+    }
+    Ok(winner)
+}
+
+pub fn new(
+    vm: &mut VirtualMachine,
+    typ: PyObjectRef,
+    name: &str,
+    bases: Vec<PyObjectRef>,
+    dict: PyObjectRef,
+) -> PyResult {
+    let mut mros = Vec::with_capacity(bases.len());
+    for base in &bases {
+        match _mro(base.clone()) {
+            Some(mro) => mros.push(mro),
+            None => {
+                let core = ErrorCore::new(format!("Cannot create class {}", name))
+                    .with_sub_message(format!("base {:?} is not a class", base), None);
+                let type_error = vm.context().exceptions.type_error.clone();
+                return Err(core.into_exception(vm, type_error));
+            }
+        }
+    }
+    let mro = if let Some(mro) = linearise_mro(mros) {
+        mro
+    } else {
+        let base_names: Vec<String> = bases.iter().map(|b| get_type_name(b)).collect();
+        let core =
+            ErrorCore::new("Cannot create a consistent method resolution order (MRO)".to_string())
+                .with_sub_message(format!("for bases {}", base_names.join(", ")), None);
+        let type_error = vm.context().exceptions.type_error.clone();
+        return Err(core.into_exception(vm, type_error));
+    };
     Ok(PyObject::new(
         PyObjectKind::Class {
             name: String::from(name),
@@ -298,8 +507,9 @@ fn type_prepare(vm: &mut VirtualMachine, _args: PyFuncArgs) -> PyResult {
 
 #[cfg(test)]
 mod tests {
+    use super::super::super::vm::VirtualMachine;
     use super::{linearise_mro, new};
-    use super::{IdProtocol, PyContext, PyObjectRef};
+    use super::{IdProtocol, PyObjectRef};
 
     fn map_ids(obj: Option<Vec<PyObjectRef>>) -> Option<Vec<usize>> {
         match obj {
@@ -310,11 +520,12 @@ mod tests {
 
     #[test]
     fn test_linearise() {
-        let context = PyContext::new();
-        let object = context.object;
-        let type_type = context.type_type;
+        let mut vm = VirtualMachine::new();
+        let object = vm.ctx.object();
+        let type_type = vm.ctx.type_type();
 
         let a = new(
+            &mut vm,
             type_type.clone(),
             "A",
             vec![object.clone()],
@@ -322,6 +533,7 @@ mod tests {
         )
         .unwrap();
         let b = new(
+            &mut vm,
             type_type.clone(),
             "B",
             vec![object.clone()],
@@ -0,0 +1,322 @@
+/*
+ * Optional static type-inference pass over function/class annotations.
+ *
+ * This is strictly opt-in: untyped code is completely unaffected, and nothing
+ * here runs unless a class body explicitly sets `__type_checked__ = True`
+ * (checked by `check_class_dict`, which `objtype::type_new` calls for every
+ * class statement). Subtype relations are decided against the *runtime* class
+ * objects from `objtype` (`issubclass`, `get_type_name`) rather than a
+ * separate static type universe, so a user-defined class and its annotations
+ * always agree.
+ *
+ * The algorithm is Hindley-Milner style unification: a `Substitution` maps
+ * type variables to the type they've been bound to, and `unify` merges two
+ * types under that substitution, binding free variables as needed.
+ */
+
+use std::collections::HashMap;
+
+use super::error::{ErrorCore, Location};
+use super::obj::{objbool, objdict, objstr, objtype};
+use super::pyobject::{IdProtocol, PyObjectRef, PyResult, TypeProtocol};
+use super::vm::VirtualMachine;
+
+pub type TypeVarId = usize;
+
+#[derive(Debug, Clone)]
+pub enum Type {
+    /// Unifies with anything; the type of `None` and other statically
+    /// uninteresting values.
+    Bottom,
+    /// A concrete runtime class, e.g. `int`, `str`, a user-defined class.
+    Concrete(PyObjectRef),
+    /// A free type variable. `bound` holds the admissible concrete classes
+    /// for a `TypeVar(..., bound=...)`/constrained type variable; `None`
+    /// means unconstrained.
+    Var(TypeVarId, Option<Vec<PyObjectRef>>),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Substitution {
+    bindings: HashMap<TypeVarId, Type>,
+    // Bounds are tracked separately from `bindings` (rather than inline on
+    // whatever `Type::Var` a variable happens to get aliased to) so that
+    // merging the bounds of two unified bounded variables has somewhere to
+    // live without binding a variable to itself.
+    bounds: HashMap<TypeVarId, Vec<PyObjectRef>>,
+}
+
+impl Substitution {
+    pub fn new() -> Self {
+        Substitution {
+            bindings: HashMap::new(),
+            bounds: HashMap::new(),
+        }
+    }
+
+    /// Follow variable bindings until hitting a concrete type, an
+    /// unbound variable, or bottom. An unbound variable is returned with
+    /// its effective bound: whatever was last recorded via `set_bound`, or
+    /// else the bound it was originally constructed with.
+    fn resolve(&self, ty: &Type) -> Type {
+        match ty {
+            Type::Var(id, inline_bound) => match self.bindings.get(id) {
+                Some(bound_ty) => self.resolve(bound_ty),
+                None => {
+                    let bound = self
+                        .bounds
+                        .get(id)
+                        .cloned()
+                        .or_else(|| inline_bound.clone());
+                    Type::Var(*id, bound)
+                }
+            },
+            other => other.clone(),
+        }
+    }
+
+    fn bind(&mut self, id: TypeVarId, ty: Type) {
+        self.bindings.insert(id, ty);
+    }
+
+    fn set_bound(&mut self, id: TypeVarId, bound: Vec<PyObjectRef>) {
+        self.bounds.insert(id, bound);
+    }
+}
+
+fn occurs(subst: &Substitution, id: TypeVarId, ty: &Type) -> bool {
+    match subst.resolve(ty) {
+        Type::Var(other_id, _) => other_id == id,
+        _ => false,
+    }
+}
+
+fn within_bound(candidate: &PyObjectRef, bound: &[PyObjectRef]) -> bool {
+    bound
+        .iter()
+        .any(|admissible| objtype::issubclass(candidate, admissible))
+}
+
+/// Intersect two bound sets: a class (from either side) survives iff it is
+/// admissible under *both* constraints, e.g. with bounds `{Base}` and
+/// `{Derived}` where `Derived <: Base`, `Derived` is within both bounds (it
+/// satisfies `Base` by being a subclass of it, and trivially satisfies
+/// itself), so it survives even though a naive one-sided filter of `a` by
+/// `b` alone would see no overlap and report an empty intersection.
+fn intersect_bounds(a: &[PyObjectRef], b: &[PyObjectRef]) -> Vec<PyObjectRef> {
+    let mut seen = std::collections::HashSet::new();
+    a.iter()
+        .chain(b.iter())
+        .filter(|candidate| within_bound(candidate, a) && within_bound(candidate, b))
+        .filter(|candidate| seen.insert(candidate.get_id()))
+        .cloned()
+        .collect()
+}
+
+/// Unify two types under `subst`, mutating it with any new bindings.
+/// - `Bottom` unifies with anything.
+/// - Two concrete classes unify iff one is an `issubclass` of the other.
+/// - A free variable binds to the other side after an occurs-check; a
+///   bounded variable unifying with a concrete type requires that type to
+///   be within the bound.
+/// - Two bounded variables unify by intersecting their bound sets; an empty
+///   intersection is an error.
+pub fn unify(subst: &mut Substitution, a: &Type, b: &Type) -> Result<(), ErrorCore> {
+    let a = subst.resolve(a);
+    let b = subst.resolve(b);
+    match (&a, &b) {
+        (Type::Bottom, _) | (_, Type::Bottom) => Ok(()),
+
+        (Type::Concrete(x), Type::Concrete(y)) => {
+            if objtype::issubclass(x, y) || objtype::issubclass(y, x) {
+                Ok(())
+            } else {
+                Err(ErrorCore::new(format!(
+                    "cannot unify '{}' with '{}'",
+                    objtype::get_type_name(x),
+                    objtype::get_type_name(y)
+                )))
+            }
+        }
+
+        (Type::Var(id, bound), Type::Concrete(c)) | (Type::Concrete(c), Type::Var(id, bound)) => {
+            if let Some(bound) = bound {
+                if !within_bound(c, bound) {
+                    return Err(ErrorCore::new(format!(
+                        "'{}' is not within the bound of type variable",
+                        objtype::get_type_name(c)
+                    )));
+                }
+            }
+            if occurs(subst, *id, &Type::Concrete(c.clone())) {
+                return Err(ErrorCore::new(
+                    "occurs check failed: type variable refers to itself".to_string(),
+                ));
+            }
+            subst.bind(*id, Type::Concrete(c.clone()));
+            Ok(())
+        }
+
+        (Type::Var(id1, bound1), Type::Var(id2, bound2)) => {
+            if id1 == id2 {
+                return Ok(());
+            }
+            let merged_bound = match (bound1, bound2) {
+                (None, None) => None,
+                (Some(bound), None) | (None, Some(bound)) => Some(bound.clone()),
+                (Some(b1), Some(b2)) => {
+                    let intersection = intersect_bounds(b1, b2);
+                    if intersection.is_empty() {
+                        return Err(ErrorCore::new(
+                            "incompatible bounded type variables: empty intersection".to_string(),
+                        ));
+                    }
+                    Some(intersection)
+                }
+            };
+            // Alias id1 to id2 so later lookups (and occurs-checks) follow
+            // the chain to id2, and record the merged bound against id2
+            // itself via `set_bound` - not by binding id2 to a `Type::Var`
+            // pointing at itself - so a later `unify(Var(id2), Concrete(c))`
+            // actually enforces the merged bound instead of treating id2 as
+            // unconstrained.
+            if let Some(bound) = &merged_bound {
+                subst.set_bound(*id2, bound.clone());
+            }
+            subst.bind(*id1, Type::Var(*id2, merged_bound));
+            Ok(())
+        }
+    }
+}
+
+/// Maps a free identifier (e.g. a name referenced in an annotation but not
+/// bound locally) to the type it stands for. Plugged in by the caller so
+/// this module stays independent of scoping/name resolution.
+pub type Resolver<'a> = dyn Fn(&str) -> Option<Type> + 'a;
+
+/// One function- or class-level annotation to check, with the span of the
+/// expression it was written on so errors can point back at the source.
+pub struct Annotation {
+    pub declared: Type,
+    pub inferred: Type,
+    pub location: Option<Location>,
+}
+
+/// Drives unification over a batch of annotations, in the style of a single
+/// compiler checking pass. Callers lower whatever they're checking (class
+/// attributes today, via `check_class_dict`; function parameters/returns
+/// once the bytecode compiler's AST is plumbed through here) into
+/// `Annotation`s - using `resolver` to turn bare names into `Type`s - before
+/// calling `check`.
+pub struct Checker<'a> {
+    subst: Substitution,
+    resolver: &'a Resolver<'a>,
+}
+
+impl<'a> Checker<'a> {
+    pub fn new(resolver: &'a Resolver<'a>) -> Self {
+        Checker {
+            subst: Substitution::new(),
+            resolver,
+        }
+    }
+
+    pub fn resolve_name(&self, name: &str) -> Option<Type> {
+        (self.resolver)(name)
+    }
+
+    /// Check one annotation, returning a structured error located at the
+    /// annotation's span on failure.
+    pub fn check(&mut self, annotation: &Annotation) -> Result<(), ErrorCore> {
+        unify(&mut self.subst, &annotation.declared, &annotation.inferred).map_err(|core| {
+            let core = core.with_sub_message(
+                "while checking an annotated expression".to_string(),
+                annotation.location.clone(),
+            );
+            match &annotation.location {
+                Some(location) => core.with_location(location.clone()),
+                None => core,
+            }
+        })
+    }
+
+    /// Check a whole batch, short-circuiting on the first failure (matches
+    /// how `new()`/`type_new` report the first error they hit rather than
+    /// collecting every one).
+    pub fn check_all(&mut self, annotations: &[Annotation]) -> Result<(), ErrorCore> {
+        for annotation in annotations {
+            self.check(annotation)?;
+        }
+        Ok(())
+    }
+}
+
+/// The pass's actual entry point: called by `objtype::type_new` for every
+/// class statement (once its namespace dict is known to actually be a
+/// dict). It is a no-op unless the class body opted in with
+/// `__type_checked__ = True`, which keeps ordinary, untyped classes
+/// completely unaffected.
+///
+/// Scope: this checks class-attribute annotations only, against the runtime
+/// type of the value actually bound to each name. Function parameter/return
+/// annotations, and locations pointing at the offending expression's span,
+/// both need the bytecode compiler's AST threaded through here, which this
+/// tree does not have; `location` is `None` for every `Annotation` built
+/// below until that's wired up.
+///
+/// An annotation written as a string (e.g. `x: "Foo"`, a forward reference
+/// to a name not yet bound at class-body time) is looked up by name in the
+/// class's own namespace via `Checker::resolve_name` rather than unified
+/// directly - a string object is never itself the declared type.
+pub fn check_class_dict(vm: &mut VirtualMachine, dict: &PyObjectRef) -> PyResult<()> {
+    let members = objdict::get_elements(dict);
+
+    let opted_in = match members.get("__type_checked__") {
+        Some(flag) => objbool::boolval(vm, flag.clone())?,
+        None => false,
+    };
+    if !opted_in {
+        return Ok(());
+    }
+
+    let annotations = match members.get("__annotations__") {
+        Some(annotations) => annotations.clone(),
+        None => return Ok(()),
+    };
+    let annotated = objdict::get_elements(&annotations);
+
+    let resolver: &Resolver<'_> = &|name: &str| members.get(name).cloned().map(Type::Concrete);
+    let mut checker = Checker::new(resolver);
+
+    let mut batch = Vec::new();
+    for (name, declared) in annotated.iter() {
+        let value = match members.get(name) {
+            Some(value) => value,
+            None => continue,
+        };
+        let declared_ty = if objtype::issubclass(&declared.typ(), &vm.ctx.str_type()) {
+            match checker.resolve_name(&objstr::get_value(declared)) {
+                Some(ty) => ty,
+                // An unresolvable forward reference isn't an error here -
+                // there's simply nothing yet to check it against.
+                None => continue,
+            }
+        } else {
+            Type::Concrete(declared.clone())
+        };
+        batch.push(Annotation {
+            declared: declared_ty,
+            inferred: Type::Concrete(value.typ()),
+            location: None,
+        });
+    }
+
+    checker.check_all(&batch).map_err(|core| {
+        let core = core.with_sub_message(
+            "while checking the class's annotated attributes".to_string(),
+            None,
+        );
+        let type_error = vm.context().exceptions.type_error.clone();
+        core.into_exception(vm, type_error)
+    })
+}